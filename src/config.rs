@@ -0,0 +1,236 @@
+//! Persistent configuration / serial-number region writer.
+//!
+//! Printers need per-unit data (serial number, calibration constants, model string)
+//! that survives a firmware reflash. This writes a length-prefixed key/value blob into
+//! a dedicated page at the top of the `Nvm` region (chosen from the target's
+//! `memory_map`), flashed independently of firmware via `DownloadOptions { skip_erase:
+//! false, do_chip_erase: false, .. }` so only the config page is erased and touched.
+//!
+//! Layout (all fields little-endian):
+//! ```text
+//! header: entry_count: u32, total_len: u32
+//! entry*: key_len: u16, key: [u8; key_len], value_len: u16, value: [u8; value_len]
+//! ```
+//! Entries are packed back-to-back after the header so reads can recover the exact map,
+//! including values well past 100 bytes; the page is padded to the flash write
+//! granularity with zero words.
+
+use std::collections::HashMap;
+
+use probe_rs::{
+    config::{MemoryRegion, NvmRegion},
+    flashing::{download_file_with_options, DownloadOptions, Format},
+    Error, MemoryInterface, Session,
+};
+
+const HEADER_LEN: usize = 8;
+
+/// STM32 flash is programmed in aligned words (F1: 16-bit half-words) or double-words
+/// (L4: 64-bit), so the page is always padded out to this granularity.
+const WRITE_GRANULARITY: usize = 8;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Target has no `Nvm` region to carve a config page out of.
+    NoFlashRegion,
+    /// The config page's contents aren't a valid encoded blob (e.g. an erased,
+    /// all-`0xFF` page that was never written).
+    Corrupt,
+    /// The encoded entries don't fit in the config page; writing them anyway would
+    /// overflow into whatever flash precedes the page (e.g. firmware).
+    TooLarge { encoded_len: usize, page_len: usize },
+    Io(std::io::Error),
+    Flash(String),
+    Probe(Error),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::NoFlashRegion => write!(f, "Target has no flash region for a config page"),
+            ConfigError::Corrupt => write!(f, "Config page contents are not a valid config blob"),
+            ConfigError::TooLarge { encoded_len, page_len } => write!(
+                f,
+                "Encoded config ({} bytes) does not fit in the {}-byte config page",
+                encoded_len, page_len
+            ),
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Flash(err) => write!(f, "{}", err),
+            ConfigError::Probe(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(err: std::io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<Error> for ConfigError {
+    fn from(err: Error) -> Self {
+        ConfigError::Probe(err)
+    }
+}
+
+fn config_page(session: &Session) -> Result<NvmRegion, ConfigError> {
+    session
+        .target()
+        .memory_map
+        .iter()
+        .filter_map(|region| match region.clone() {
+            MemoryRegion::Nvm(region) => Some(region),
+            _ => None,
+        })
+        .max_by_key(|region| region.range.end)
+        .map(|region| {
+            let page_size = region.page_size.unwrap_or(region.range.len() as u32) as u64;
+            let page_start = region.range.end - page_size;
+            NvmRegion {
+                range: page_start..region.range.end,
+                ..region
+            }
+        })
+        .ok_or(ConfigError::NoFlashRegion)
+}
+
+fn encode(entries: &HashMap<String, String>) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (key, value) in entries {
+        body.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        body.extend_from_slice(key.as_bytes());
+        body.extend_from_slice(&(value.len() as u16).to_le_bytes());
+        body.extend_from_slice(value.as_bytes());
+    }
+
+    let mut blob = Vec::with_capacity(HEADER_LEN + body.len());
+    blob.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    blob.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    blob.extend_from_slice(&body);
+
+    while blob.len() % WRITE_GRANULARITY != 0 {
+        blob.push(0);
+    }
+
+    blob
+}
+
+/// Decode a config page blob, rejecting anything that isn't a well-formed encoding of
+/// `encode` instead of indexing blindly into it. An erased (all-`0xFF`) page, which is
+/// what `read_config` sees on a freshly-flashed unit that never had `write_config`
+/// called, decodes its header to implausible `entry_count`/`total_len` values and must
+/// be caught here rather than panicking on the first malformed slice.
+fn decode(blob: &[u8]) -> Result<HashMap<String, String>, ConfigError> {
+    if blob.len() < HEADER_LEN {
+        return Err(ConfigError::Corrupt);
+    }
+
+    let entry_count = u32::from_le_bytes(blob[0..4].try_into().unwrap()) as usize;
+    let total_len = u32::from_le_bytes(blob[4..8].try_into().unwrap()) as usize;
+
+    if HEADER_LEN + total_len > blob.len() {
+        return Err(ConfigError::Corrupt);
+    }
+
+    let body = &blob[HEADER_LEN..HEADER_LEN + total_len];
+    let mut entries = HashMap::new();
+    let mut offset = 0;
+
+    for _ in 0..entry_count {
+        if offset + 2 > body.len() {
+            return Err(ConfigError::Corrupt);
+        }
+        let key_len = u16::from_le_bytes(body[offset..offset + 2].try_into().unwrap()) as usize;
+        offset += 2;
+
+        if offset + key_len > body.len() {
+            return Err(ConfigError::Corrupt);
+        }
+        let key = String::from_utf8_lossy(&body[offset..offset + key_len]).into_owned();
+        offset += key_len;
+
+        if offset + 2 > body.len() {
+            return Err(ConfigError::Corrupt);
+        }
+        let value_len = u16::from_le_bytes(body[offset..offset + 2].try_into().unwrap()) as usize;
+        offset += 2;
+
+        if offset + value_len > body.len() {
+            return Err(ConfigError::Corrupt);
+        }
+        let value = String::from_utf8_lossy(&body[offset..offset + value_len]).into_owned();
+        offset += value_len;
+
+        entries.insert(key, value);
+    }
+
+    Ok(entries)
+}
+
+/// `download_file_with_options` with `skip_erase: false, do_chip_erase: false` so
+/// probe-rs's flash loader erases just the sector(s) covered by the written range,
+/// leaving the rest of flash (including firmware) untouched. `skip_erase: true` would
+/// skip that per-page erase entirely, and since NOR flash can only clear bits on
+/// erase, rewriting the page with different or shorter data than a previous write
+/// would silently AND the new bytes against stale ones instead of producing the
+/// intended blob.
+fn config_download_options() -> DownloadOptions<'static> {
+    let mut options = DownloadOptions::default();
+    options.skip_erase = false;
+    options.do_chip_erase = false;
+    options
+}
+
+fn write_page(
+    session: &mut Session,
+    page: &NvmRegion,
+    data: &[u8],
+    options: DownloadOptions,
+) -> Result<(), ConfigError> {
+    let path = std::env::temp_dir().join("martel-config-page.bin");
+    std::fs::write(&path, data)?;
+
+    download_file_with_options(session, &path, Format::Bin(page.range.start), options)
+        .map_err(|err| ConfigError::Flash(err.to_string()))
+}
+
+/// Write `entries` into the dedicated config page at the top of flash, erasing and
+/// reprogramming only that page.
+pub fn write_config(
+    session: &mut Session,
+    entries: &HashMap<String, String>,
+) -> Result<(), ConfigError> {
+    let page = config_page(session)?;
+    let blob = encode(entries);
+
+    if blob.len() > page.range.len() as usize {
+        return Err(ConfigError::TooLarge {
+            encoded_len: blob.len(),
+            page_len: page.range.len() as usize,
+        });
+    }
+
+    write_page(session, &page, &blob, config_download_options())
+}
+
+/// Read back the config page and decode it into the original key/value map. Returns
+/// `Err(ConfigError::Corrupt)` for an unwritten (erased) page rather than panicking.
+pub fn read_config(session: &mut Session) -> Result<HashMap<String, String>, ConfigError> {
+    let page = config_page(session)?;
+
+    let mut core = session.core(0)?;
+    let mut blob = vec![0u8; page.range.len() as usize];
+    core.read_8(page.range.start, &mut blob)?;
+
+    decode(&blob)
+}
+
+/// Erase the config page, discarding any stored entries, without touching firmware.
+pub fn erase_config(session: &mut Session) -> Result<(), ConfigError> {
+    let page = config_page(session)?;
+    let erased = vec![0xFFu8; page.range.len() as usize];
+
+    write_page(session, &page, &erased, config_download_options())
+}
@@ -0,0 +1,264 @@
+//! Multi-format firmware loading with address-range validation.
+//!
+//! `download_file_with_options` only ever saw `Format::Hex`. This module picks the
+//! format from a file extension (or an explicit [`FirmwareFormat`]), and for raw
+//! binaries accepts a base load address (defaulting to the start of the first `Nvm`
+//! region in the target's `memory_map`). Before handing anything to probe-rs we check
+//! every segment's address range against the enumerated flash regions, so a `.bin`
+//! built for the wrong density, or a HEX file with a stray high-address record, is
+//! rejected up front instead of failing mid-erase.
+
+use std::{ffi::OsStr, fmt, path::Path};
+
+use probe_rs::{
+    config::{MemoryRegion, NvmRegion},
+    flashing::{DownloadOptions, Format},
+    Session,
+};
+
+/// Firmware image format, mirroring `probe_rs::flashing::Format` but selectable from a
+/// file extension as well as explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirmwareFormat {
+    Hex,
+    Bin { base_address: Option<u64> },
+    Elf,
+}
+
+impl FirmwareFormat {
+    /// Guess the format from a file's extension: `.hex` -> Hex, `.elf`/`.axf` -> Elf,
+    /// anything else (including `.bin`) -> Bin with no explicit base address.
+    pub fn from_path(path: &Path) -> FirmwareFormat {
+        match path.extension().and_then(OsStr::to_str) {
+            Some(ext) if ext.eq_ignore_ascii_case("hex") => FirmwareFormat::Hex,
+            Some(ext) if ext.eq_ignore_ascii_case("elf") || ext.eq_ignore_ascii_case("axf") => {
+                FirmwareFormat::Elf
+            }
+            _ => FirmwareFormat::Bin { base_address: None },
+        }
+    }
+
+    fn into_probe_rs_format(self, flash_regions: &[NvmRegion]) -> Result<Format, FirmwareError> {
+        match self {
+            FirmwareFormat::Hex => Ok(Format::Hex),
+            FirmwareFormat::Elf => Ok(Format::Elf),
+            FirmwareFormat::Bin { base_address } => {
+                let base_address = base_address
+                    .or_else(|| flash_regions.first().map(|region| region.range.start))
+                    .ok_or(FirmwareError::NoFlashRegion)?;
+                Ok(Format::Bin(base_address))
+            }
+        }
+    }
+}
+
+/// A firmware segment that falls outside every enumerated flash region.
+#[derive(Debug, Clone)]
+pub struct OutOfRangeSegment {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl fmt::Display for OutOfRangeSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}..{:#010x}", self.start, self.end)
+    }
+}
+
+#[derive(Debug)]
+pub enum FirmwareError {
+    /// No `Nvm` region exists on this target to default a raw binary's base address to.
+    NoFlashRegion,
+    /// One or more image segments fall outside every enumerated flash region.
+    OutOfRange(Vec<OutOfRangeSegment>),
+    /// Image could not be read from disk.
+    Io(std::io::Error),
+    /// probe-rs failed to flash the image.
+    Flash(String),
+}
+
+impl fmt::Display for FirmwareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirmwareError::NoFlashRegion => {
+                write!(f, "Target has no flash region to default a base address to")
+            }
+            FirmwareError::OutOfRange(segments) => {
+                write!(f, "Firmware image has segments outside any flash region: ")?;
+                for (i, segment) in segments.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", segment)?;
+                }
+                Ok(())
+            }
+            FirmwareError::Io(err) => write!(f, "{}", err),
+            FirmwareError::Flash(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for FirmwareError {}
+
+impl From<std::io::Error> for FirmwareError {
+    fn from(err: std::io::Error) -> Self {
+        FirmwareError::Io(err)
+    }
+}
+
+fn flash_regions(session: &Session) -> Vec<NvmRegion> {
+    session
+        .target()
+        .memory_map
+        .iter()
+        .filter_map(|region| match region.clone() {
+            MemoryRegion::Nvm(region) => Some(region),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parse an Intel HEX file's data records into `(start, end)` address ranges, honouring
+/// extended linear (type `04`) and extended segment (type `02`) address records. This
+/// mirrors just enough of the format to range-check segments before flashing; the
+/// actual parsing for programming is left to probe-rs.
+fn hex_segments(path: &Path) -> Result<Vec<(u64, u64)>, FirmwareError> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut segments = Vec::new();
+    let mut upper_address = 0u32;
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if !line.starts_with(':') || line.len() < 11 {
+            continue;
+        }
+
+        let bytes: Vec<u8> = (1..line.len())
+            .step_by(2)
+            .filter_map(|i| line.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+            .collect();
+
+        if bytes.len() < 5 {
+            continue;
+        }
+
+        let byte_count = bytes[0] as usize;
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let record_type = bytes[3];
+
+        match record_type {
+            // Data record.
+            0x00 => {
+                let start = (upper_address.wrapping_add(address)) as u64;
+                segments.push((start, start + byte_count as u64));
+            }
+            // Extended linear address: sets bits [31:16] of the base address.
+            0x04 if bytes.len() >= 6 => {
+                upper_address = (u16::from_be_bytes([bytes[4], bytes[5]]) as u32) << 16;
+            }
+            // Extended segment address: sets bits [19:4] of the base address.
+            0x02 if bytes.len() >= 6 => {
+                upper_address = (u16::from_be_bytes([bytes[4], bytes[5]]) as u32) << 4;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Parse a 32-bit ELF file's `PT_LOAD` program headers into `(start, end)` address
+/// ranges, using each segment's physical address and file size.
+fn elf_segments(path: &Path) -> Result<Vec<(u64, u64)>, FirmwareError> {
+    const PT_LOAD: u32 = 1;
+
+    let data = std::fs::read(path)?;
+
+    if data.len() < 52 || &data[0..4] != b"\x7fELF" || data[4] != 1 {
+        // Not a recognisable 32-bit ELF (these targets are all 32-bit Cortex-M); skip
+        // range-checking rather than mis-parsing a layout we don't understand.
+        return Ok(Vec::new());
+    }
+
+    let e_phoff = u32::from_le_bytes(data[28..32].try_into().unwrap()) as usize;
+    let e_phentsize = u16::from_le_bytes(data[42..44].try_into().unwrap()) as usize;
+    let e_phnum = u16::from_le_bytes(data[44..46].try_into().unwrap()) as usize;
+
+    let mut segments = Vec::new();
+    for i in 0..e_phnum {
+        let offset = e_phoff + i * e_phentsize;
+        if offset + 20 > data.len() {
+            break;
+        }
+
+        let p_type = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        if p_type != PT_LOAD {
+            continue;
+        }
+
+        let p_paddr = u32::from_le_bytes(data[offset + 12..offset + 16].try_into().unwrap()) as u64;
+        let p_filesz = u32::from_le_bytes(data[offset + 16..offset + 20].try_into().unwrap()) as u64;
+
+        if p_filesz > 0 {
+            segments.push((p_paddr, p_paddr + p_filesz));
+        }
+    }
+
+    Ok(segments)
+}
+
+/// Validate that every byte of `segments` falls inside one of `flash_regions`.
+fn validate_segments(
+    segments: &[(u64, u64)],
+    flash_regions: &[NvmRegion],
+) -> Result<(), FirmwareError> {
+    let out_of_range: Vec<OutOfRangeSegment> = segments
+        .iter()
+        .filter(|(start, end)| {
+            !flash_regions
+                .iter()
+                .any(|region| region.range.start <= *start && *end <= region.range.end)
+        })
+        .map(|(start, end)| OutOfRangeSegment {
+            start: *start,
+            end: *end,
+        })
+        .collect();
+
+    if out_of_range.is_empty() {
+        Ok(())
+    } else {
+        Err(FirmwareError::OutOfRange(out_of_range))
+    }
+}
+
+/// Load `path` onto the target in the given `format` (use [`FirmwareFormat::from_path`]
+/// to derive it from the file extension; raw binaries without a base address default
+/// to the start of the first flash region), after validating that the image's address range(s) fall entirely inside
+/// the target's enumerated flash regions.
+pub fn download_firmware(
+    session: &mut Session,
+    path: &Path,
+    format: FirmwareFormat,
+    mut options: DownloadOptions,
+) -> Result<(), FirmwareError> {
+    let flash_regions = flash_regions(session);
+
+    let probe_rs_format = format.into_probe_rs_format(&flash_regions)?;
+
+    let segments = match probe_rs_format {
+        Format::Bin(base_address) => {
+            let len = std::fs::metadata(path)?.len();
+            vec![(base_address, base_address + len)]
+        }
+        Format::Hex => hex_segments(path)?,
+        Format::Elf => elf_segments(path)?,
+    };
+    validate_segments(&segments, &flash_regions)?;
+
+    options.verify = true;
+
+    probe_rs::flashing::download_file_with_options(session, path, probe_rs_format, options)
+        .map_err(|err| FirmwareError::Flash(err.to_string()))
+}
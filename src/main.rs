@@ -1,22 +1,20 @@
-use std::{path::Path, sync::Arc, time::Duration, collections::HashMap};
+use std::{cell::RefCell, path::Path, sync::Arc, time::Duration, collections::HashMap};
 
 use probe_rs::{
     architecture::arm::DpAddress,
     config::{add_target_from_yaml, get_target_by_name, MemoryRegion, TargetSelector},
-    flashing::{
-        download_file, download_file_with_options, erase_all, DownloadOptions, FlashProgress,
-        Format, ProgressEvent,
-    },
+    flashing::{download_file, erase_all, DownloadOptions, FlashProgress, ProgressEvent},
     DebugProbeError, MemoryInterface, Permissions, Probe, Target, WireProtocol,
 };
 
-const STM32F1: &[u8] = include_bytes!("../res/STM32F1xx.yaml");
-const STM32F2: &[u8] = include_bytes!("../res/STM32F2xx.yaml");
-const STM32L4: &[u8] = include_bytes!("../res/STM32L4xx.yaml");
+mod config;
+mod firmware;
+mod option_bytes;
+mod progress;
+mod target;
+mod verify;
 
-const STM32F1xID: u32 = 0x1ba01477;
-const STM32F2xID: u32 = 0x2ba01477;
-const STM32L4xID: u32 = 0x2ba01477;
+use progress::{FlashProgressHandler, TerminalSink};
 
 macro_rules! extract_resource {
     ($from:literal => $to:literal) => {
@@ -29,18 +27,6 @@ macro_rules! extract_resource {
 }
 
 fn main() -> Result<(), probe_rs::Error> {
-    let device_ids = HashMap::from([
-        (0x412, "STM32F10xxx Low Density"),
-        (0x410, "STM32F10xxx Medium Density"),
-        (0x414, "STM32F10xxx High Density"),
-        (0x430, "STM32F10xxx XL Density"),
-        (0x418, "STM32F10xxx Connectivity"),
-        (0x411, "STM32F20xxx / STM32F21xxx"),
-        (0x435, "STM32L43xxx / STM32L44xxx"),
-        (0x462, "STM32L45xxx / STM32L46xxx"),
-        (0x464, "STM32L41xxx / STM32L42xxx"),
-    ]);
-
     let probe_list = Probe::list_all();
 
     println!("Probes:");
@@ -49,128 +35,117 @@ fn main() -> Result<(), probe_rs::Error> {
         .for_each(|probe| println!("Probe found => {}", probe.identifier));
     println!("--------------------");
 
-    let mut probe = Probe::open(&probe_list[0])?;
+    let probe = Probe::open(&probe_list[0])?;
 
-    probe.attach_to_unspecified()?;
+    let (mut session, target, density) = target::identify_and_attach(probe)?;
 
-    // Identify which processor is connected by looking at the ID in the DBGMCU_IDCODE
-    // register.
-    let target_id = probe
-        .try_into_arm_interface()
-        .map_err(|(_, err)| probe_rs::Error::from(err))
-        .and_then(|mut interface| {
-            let mut interface = interface.initialize_unspecified()?;
-            if let Some(info) = interface.read_chip_info_from_rom_table(DpAddress::Default)? {
-                println!("Info: {}", info.to_string());
-            }
-            Ok(())
-        })?;
-
-    // // Extract resources for the identified target.
-    // let target_name = match target_id {
-    //     0x2ba01477 => {
-    //         extract_resource!("STM32L4xx.yaml" => "target");
-    //         add_target_from_yaml(Path::new("./target"))?;
-    //         "STM32L433RCTx".to_string()
-    //     }
-    //     0x1ba01477 => "STM32F103RC".to_string(),
-    //     id => format!("Unknown({})", id),
-    // };
-
-    // println!("Found target: {}", target_name);
-
-    // let mut session = Probe::open(&probe_list[0])
-    //     .map_err(probe_rs::Error::from)
-    //     .and_then(|probe| probe.attach(target_name, Permissions::new().allow_erase_all()))?;
-
-    // println!("pog: {:#?}", session.target());
-
-    // let mut ram = Vec::new();
-    // let mut flash = Vec::new();
-    // let mut generic = Vec::new();
-    // session
-    //     .target()
-    //     .memory_map
-    //     .iter()
-    //     .for_each(|region| match region.clone() {
-    //         MemoryRegion::Generic(gen_region) => generic.push(gen_region),
-    //         MemoryRegion::Ram(ram_region) => ram.push(ram_region),
-    //         MemoryRegion::Nvm(flash_region) => flash.push(flash_region),
-    //     });
-
-    // println!();
-    // println!("Memory regions");
-    // ram.iter().for_each(|region| {
-    //     println!(
-    //         "Found RAM Region => {} : {:#x?}",
-    //         region.name.as_ref().unwrap_or(&"unnamed".to_string()),
-    //         region.range
-    //     );
-    // });
-    // flash.iter().for_each(|region| {
-    //     println!(
-    //         "Found Flash Region => {} : {:#x?}",
-    //         region.name.as_ref().unwrap_or(&"unnamed".to_string()),
-    //         region.range
-    //     );
-    // });
-    // generic.iter().for_each(|region| {
-    //     println!(
-    //         "Found Generic Region => {} : {:#x?}",
-    //         region.name.as_ref().unwrap_or(&"unnamed".to_string()),
-    //         region.range
-    //     );
-    // });
-
-    // let flash = flash[0].clone();
-    // let ram = ram[0].clone();
-
-    // println!("cores: {:?}", session.list_cores());
-
-    // let core_halted = if let Ok(mut core) = session.core(0) {
-    //     core.reset_and_halt(Duration::from_secs(1))?;
-    //     core.core_halted()?
-    // } else {
-    //     false
-    // };
-
-    // if core_halted {
-    //     extract_resource!("../res/MCP1800_433.hex" => "./firmware");
-
-    //     let mut options = DownloadOptions::default();
-    //     let progress = FlashProgress::new(flash_progress_handler);
-    //     options.progress = Some(&progress);
-    //     options.do_chip_erase = true;
-    //     options.skip_erase = false;
-    //     options.verify = true;
-
-    //     download_file_with_options(&mut session, "./firmware", Format::Hex, options)
-    //         .expect("Failed to flash processor.");
-    // } else {
-    //     println!("ERROR => Failed to halt core");
-    // }
+    println!("Found target: {} ({})", target.name, density);
 
-    Ok(())
-}
+    let mut ram = Vec::new();
+    let mut flash = Vec::new();
+    let mut generic = Vec::new();
+    session
+        .target()
+        .memory_map
+        .iter()
+        .for_each(|region| match region.clone() {
+            MemoryRegion::Generic(gen_region) => generic.push(gen_region),
+            MemoryRegion::Ram(ram_region) => ram.push(ram_region),
+            MemoryRegion::Nvm(flash_region) => flash.push(flash_region),
+        });
+
+    println!();
+    println!("Memory regions");
+    ram.iter().for_each(|region| {
+        println!(
+            "Found RAM Region => {} : {:#x?}",
+            region.name.as_ref().unwrap_or(&"unnamed".to_string()),
+            region.range
+        );
+    });
+    flash.iter().for_each(|region| {
+        println!(
+            "Found Flash Region => {} : {:#x?}",
+            region.name.as_ref().unwrap_or(&"unnamed".to_string()),
+            region.range
+        );
+    });
+    generic.iter().for_each(|region| {
+        println!(
+            "Found Generic Region => {} : {:#x?}",
+            region.name.as_ref().unwrap_or(&"unnamed".to_string()),
+            region.range
+        );
+    });
+
+    println!("cores: {:?}", session.list_cores());
+
+    let core_halted = if let Ok(mut core) = session.core(0) {
+        core.reset_and_halt(Duration::from_secs(1))?;
+        core.core_halted()?
+    } else {
+        false
+    };
 
-fn flash_progress_handler(event: ProgressEvent) {
-    use ProgressEvent::*;
-    match event {
-        Initialized { flash_layout } => println!("---Program Begin---"),
-
-        StartedFilling => println!("Fill start"),
-        PageFilled { size, time } => (),
-        FailedFilling => println!("Fill fail"),
-        FinishedFilling => println!("Fill complete"),
-
-        StartedErasing => println!("Erase start"),
-        SectorErased { size, time } => (),
-        FailedErasing => println!("Erase fail"),
-        FinishedErasing => println!("Erase complete"),
-
-        StartedProgramming => println!("Program start"),
-        PageProgrammed { size, time } => (),
-        FailedProgramming => println!("Program fail"),
-        FinishedProgramming => println!("Program complete"),
+    if core_halted {
+        if let Some(regs) = option_bytes::OptionRegisters::for_target_name(&target.name) {
+            let protection = {
+                let mut core = session.core(0)?;
+                option_bytes::read_protection(&mut core, regs)
+            };
+
+            match protection {
+                Ok(option_bytes::ReadoutProtection::Level1) => {
+                    println!("WARNING => Target is read-protected (RDP level 1); mass-erasing before reflashing");
+                    erase_all(&mut session).expect("Failed to mass-erase protected target.");
+                }
+                Ok(option_bytes::ReadoutProtection::Level0) => {}
+                Err(err) => println!("WARNING => Failed to read protection level: {}", err),
+            }
+        }
+
+        extract_resource!("MCP1800_433.hex" => "firmware");
+
+        let handler = RefCell::new(FlashProgressHandler::new(TerminalSink));
+
+        let mut options = DownloadOptions::default();
+        let progress = FlashProgress::new(move |event| handler.borrow_mut().handle(event));
+        options.progress = Some(&progress);
+        // A chip erase would wipe the config page alongside firmware, defeating the
+        // point of it surviving a reflash; probe-rs's loader already erases only the
+        // sectors the firmware image actually covers.
+        options.do_chip_erase = false;
+        options.skip_erase = false;
+        options.verify = true;
+
+        firmware::download_firmware(
+            &mut session,
+            Path::new("./firmware"),
+            firmware::FirmwareFormat::Hex,
+            options,
+        )
+        .expect("Failed to flash processor.");
+
+        match config::read_config(&mut session) {
+            Ok(entries) => println!("Config page => {:?}", entries),
+            Err(config::ConfigError::Corrupt) => println!("Config page => not yet written"),
+            Err(err) => println!("WARNING => Failed to read config page: {}", err),
+        }
+
+        if let (Some(flash_region), Some(ram_region)) = (flash.first(), ram.first()) {
+            let image = verify::load_hex_into_region(Path::new("./firmware"), flash_region)
+                .expect("Failed to re-parse firmware image for verification.");
+            let expected = verify::expected_crc(&image);
+
+            match verify::verify_crc(&mut session, flash_region, ram_region.range.start, expected) {
+                Ok(true) => println!("CRC verify => OK"),
+                Ok(false) => println!("CRC verify => MISMATCH"),
+                Err(err) => println!("WARNING => Failed to run CRC verification: {}", err),
+            }
+        }
+    } else {
+        println!("ERROR => Failed to halt core");
     }
+
+    Ok(())
 }
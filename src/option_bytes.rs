@@ -0,0 +1,209 @@
+//! STM32 flash option-byte and read-out-protection (RDP) management.
+//!
+//! Option programming is independent of the main firmware download path and follows
+//! the standard STM32 unlock sequence: the flash control register is unlocked with the
+//! `FLASH_KEYR` key pair, the option area is unlocked separately with `FLASH_OPTKEYR`,
+//! then the option bytes are modified and committed. The exact program/erase bits and
+//! their order differ by family — see [`Family`] — so that part of the sequence is
+//! dispatched per family rather than shared.
+//!
+//! The main use case is locking a shipped printer's firmware by raising RDP to level 1,
+//! and detecting a protected part up front rather than letting `download_file_with_options`
+//! fail with an opaque error partway through.
+
+use std::time::{Duration, Instant};
+
+use probe_rs::{Core, Error, MemoryInterface};
+
+/// Key sequence that unlocks `FLASH_CR` for the main flash array.
+const FLASH_KEY1: u32 = 0x4567_0123;
+const FLASH_KEY2: u32 = 0xCDEF_89AB;
+
+/// Key sequence that unlocks the option-byte area once `FLASH_CR` is unlocked.
+const FLASH_OPTKEY1: u32 = 0x0819_2A3B;
+const FLASH_OPTKEY2: u32 = 0x4C5D_6E7F;
+
+const FLASH_SR_BSY: u32 = 1 << 16;
+
+/// `STM32F1xx` option bytes are a handful of emulated half-words (RDP, USER, DATA,
+/// WRP) at a fixed flash address, programmed through the classic `OPTPG`/`OPTER` pair
+/// in `FLASH_CR` and latched by the next system reset.
+mod f1 {
+    pub const FLASH_CR_OPTPG: u32 = 1 << 4;
+    pub const FLASH_CR_OPTER: u32 = 1 << 5;
+    pub const FLASH_CR_STRT: u32 = 1 << 6;
+}
+
+/// `STM32L4xx` packs all option bits into a single `FLASH_OPTR` register, committed by
+/// setting `OPTSTRT` and latched via `OBL_LAUNCH` without a separate erase step.
+mod l4 {
+    pub const FLASH_CR_OPTSTRT: u32 = 1 << 17;
+    pub const FLASH_CR_OBL_LAUNCH: u32 = 1 << 27;
+}
+
+/// Which option-byte programming sequence a target uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// Classic `OPTPG`/`OPTER` emulated option bytes (F1, F2).
+    Classic,
+    /// Packed `FLASH_OPTR` register with `OPTSTRT`/`OBL_LAUNCH` (L4).
+    Packed,
+}
+
+/// Register layout for a family's flash option-byte block.
+pub struct OptionRegisters {
+    pub family: Family,
+    pub flash_keyr: u64,
+    pub flash_optkeyr: u64,
+    pub flash_cr: u64,
+    pub flash_sr: u64,
+    pub flash_optr: u64,
+}
+
+impl OptionRegisters {
+    pub const STM32F1: OptionRegisters = OptionRegisters {
+        family: Family::Classic,
+        flash_keyr: 0x4002_2004,
+        flash_optkeyr: 0x4002_2008,
+        flash_cr: 0x4002_2010,
+        flash_sr: 0x4002_200C,
+        flash_optr: 0x1FFF_F800,
+    };
+
+    pub const STM32L4: OptionRegisters = OptionRegisters {
+        family: Family::Packed,
+        flash_keyr: 0x4002_2008,
+        flash_optkeyr: 0x4002_200C,
+        flash_cr: 0x4002_2014,
+        flash_sr: 0x4002_2010,
+        flash_optr: 0x4002_2020,
+    };
+
+    /// Pick the register layout for a target by name, or `None` if it isn't one of the
+    /// families this module knows how to program. `STM32F2xx` reuses the F1 layout:
+    /// both expose the same classic `OPTPG`/`OPTER` option-byte interface.
+    pub fn for_target_name(name: &str) -> Option<&'static OptionRegisters> {
+        if name.starts_with("STM32L4") {
+            Some(&OptionRegisters::STM32L4)
+        } else if name.starts_with("STM32F1") || name.starts_with("STM32F2") {
+            Some(&OptionRegisters::STM32F1)
+        } else {
+            None
+        }
+    }
+}
+
+const OPTION_PROGRAM_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Read-out-protection level. `Level1` → `Level0` is a destructive transition: the
+/// hardware forces a full mass erase of the main flash array before it will accept it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadoutProtection {
+    Level0,
+    Level1,
+}
+
+fn unlock(core: &mut Core, regs: &OptionRegisters) -> Result<(), Error> {
+    core.write_word_32(regs.flash_keyr, FLASH_KEY1)?;
+    core.write_word_32(regs.flash_keyr, FLASH_KEY2)?;
+    core.write_word_32(regs.flash_optkeyr, FLASH_OPTKEY1)?;
+    core.write_word_32(regs.flash_optkeyr, FLASH_OPTKEY2)?;
+    Ok(())
+}
+
+fn wait_while_busy(core: &mut Core, regs: &OptionRegisters) -> Result<(), Error> {
+    let start = Instant::now();
+    while core.read_word_32(regs.flash_sr)? & FLASH_SR_BSY != 0 {
+        if start.elapsed() > OPTION_PROGRAM_TIMEOUT {
+            return Err(Error::Timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Read the currently programmed RDP level out of `FLASH_OPTR`.
+///
+/// The RDP byte lives in bits `[7:0]`: `0xAA` is level 0 (unprotected) and anything
+/// else (in practice `0xBB` for level 1 on these parts) is treated as level 1.
+pub fn read_protection(core: &mut Core, regs: &OptionRegisters) -> Result<ReadoutProtection, Error> {
+    let optr = core.read_word_32(regs.flash_optr)?;
+    match optr & 0xFF {
+        0xAA => Ok(ReadoutProtection::Level0),
+        _ => Ok(ReadoutProtection::Level1),
+    }
+}
+
+/// `STM32F1xx`/`STM32F2xx`: erase the emulated option-byte block with `OPTER`/`STRT`
+/// (option bytes are NOR flash too, so the old RDP half-word has to be erased before
+/// it can be rewritten), then enable `OPTPG` and write the new RDP half-word paired
+/// with its bitwise complement in the upper byte, as the hardware expects. There's no
+/// `OBL_LAUNCH` bit on this family: the new option bytes take effect on the next
+/// system reset.
+fn set_protection_classic(
+    core: &mut Core,
+    regs: &OptionRegisters,
+    rdp_byte: u32,
+) -> Result<(), Error> {
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr | f1::FLASH_CR_OPTER)?;
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr | f1::FLASH_CR_STRT)?;
+    wait_while_busy(core, regs)?;
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr & !f1::FLASH_CR_OPTER)?;
+
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr | f1::FLASH_CR_OPTPG)?;
+    let packed = rdp_byte | ((!rdp_byte & 0xFF) << 8);
+    core.write_word_32(regs.flash_optr, packed)?;
+    wait_while_busy(core, regs)?;
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr & !f1::FLASH_CR_OPTPG)?;
+
+    core.reset()
+}
+
+/// `STM32L4xx`: modify the RDP field of the single packed `FLASH_OPTR` register, set
+/// `OPTSTRT` and poll `BSY`, then trigger `OBL_LAUNCH` which reloads the option bytes
+/// and resets the core.
+fn set_protection_packed(
+    core: &mut Core,
+    regs: &OptionRegisters,
+    rdp_byte: u32,
+) -> Result<(), Error> {
+    let optr = core.read_word_32(regs.flash_optr)?;
+    core.write_word_32(regs.flash_optr, (optr & !0xFF) | rdp_byte)?;
+
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr | l4::FLASH_CR_OPTSTRT)?;
+    wait_while_busy(core, regs)?;
+
+    let cr = core.read_word_32(regs.flash_cr)?;
+    core.write_word_32(regs.flash_cr, cr | l4::FLASH_CR_OBL_LAUNCH)?;
+
+    Ok(())
+}
+
+/// Program a new RDP level.
+///
+/// Raising `Level0` -> `Level1` simply reprograms the RDP byte. Lowering `Level1` ->
+/// `Level0` is destructive on real hardware: the option-byte reload forces a mass
+/// erase of the main flash array, so callers must have already accepted that (e.g. via
+/// `erase_all`) before calling this with `Level0`.
+pub fn set_protection(
+    core: &mut Core,
+    regs: &OptionRegisters,
+    level: ReadoutProtection,
+) -> Result<(), Error> {
+    let rdp_byte: u32 = match level {
+        ReadoutProtection::Level0 => 0xAA,
+        ReadoutProtection::Level1 => 0xBB,
+    };
+
+    unlock(core, regs)?;
+
+    match regs.family {
+        Family::Classic => set_protection_classic(core, regs, rdp_byte),
+        Family::Packed => set_protection_packed(core, regs, rdp_byte),
+    }
+}
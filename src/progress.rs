@@ -0,0 +1,188 @@
+//! Real progress accounting and throughput reporting for flash operations.
+//!
+//! `ProgressEvent` already carries `size`/`time` on its `*Filled`/`*Erased`/`*Programmed`
+//! variants; this tracks them against the phase totals from `Initialized { flash_layout }`
+//! to produce a percentage-complete and a running KiB/s figure per phase, and hands the
+//! result to a [`ProgressSink`] instead of hard-coding `println!` so a GUI or CI log can
+//! consume the same events a terminal does.
+
+use std::time::Duration;
+
+use probe_rs::flashing::{FlashLayout, ProgressEvent};
+
+/// One phase of a flash operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Fill,
+    Erase,
+    Program,
+}
+
+/// A structured progress update, replacing the ad-hoc `println!`s in the old handler.
+#[derive(Debug, Clone)]
+pub enum ProgressUpdate {
+    /// A phase started; `total_bytes` is the amount of work it covers.
+    PhaseStarted { phase: Phase, total_bytes: u64 },
+    /// A chunk of work completed within a phase.
+    PhaseProgress {
+        phase: Phase,
+        bytes_done: u64,
+        total_bytes: u64,
+        percent: f32,
+        throughput_kib_s: f32,
+    },
+    /// A phase failed.
+    PhaseFailed { phase: Phase },
+    /// A phase finished successfully.
+    PhaseFinished { phase: Phase },
+    /// The whole operation finished; summarises total bytes and elapsed time per phase.
+    Summary { phases: Vec<(Phase, u64, Duration)> },
+}
+
+/// Consumes [`ProgressUpdate`]s. A terminal printer, a GUI progress bar and a CI logger
+/// can all implement this to share the same accounting below.
+pub trait ProgressSink {
+    fn update(&mut self, update: ProgressUpdate);
+}
+
+/// `println!`-based [`ProgressSink`] that prints each update as a line on the terminal.
+pub struct TerminalSink;
+
+impl ProgressSink for TerminalSink {
+    fn update(&mut self, update: ProgressUpdate) {
+        match update {
+            ProgressUpdate::PhaseStarted { phase, total_bytes } => {
+                println!("{:?} start ({} bytes)", phase, total_bytes)
+            }
+            ProgressUpdate::PhaseProgress {
+                phase,
+                percent,
+                throughput_kib_s,
+                ..
+            } => println!("{:?}: {:.1}% ({:.1} KiB/s)", phase, percent, throughput_kib_s),
+            ProgressUpdate::PhaseFailed { phase } => println!("{:?} failed", phase),
+            ProgressUpdate::PhaseFinished { phase } => println!("{:?} complete", phase),
+            ProgressUpdate::Summary { phases } => {
+                println!("---Program Summary---");
+                for (phase, bytes, elapsed) in phases {
+                    let secs = elapsed.as_secs_f32().max(f32::EPSILON);
+                    println!(
+                        "{:?}: {} bytes in {:.2}s ({:.1} KiB/s)",
+                        phase,
+                        bytes,
+                        secs,
+                        (bytes as f32 / 1024.0) / secs
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct PhaseAccounting {
+    total_bytes: u64,
+    bytes_done: u64,
+    elapsed: Duration,
+}
+
+/// Accumulates bytes-per-phase and elapsed time across a flash operation, turning raw
+/// `ProgressEvent`s into [`ProgressUpdate`]s for a [`ProgressSink`].
+pub struct FlashProgressHandler<S: ProgressSink> {
+    sink: S,
+    fill: PhaseAccounting,
+    erase: PhaseAccounting,
+    program: PhaseAccounting,
+}
+
+impl<S: ProgressSink> FlashProgressHandler<S> {
+    pub fn new(sink: S) -> Self {
+        FlashProgressHandler {
+            sink,
+            fill: PhaseAccounting::default(),
+            erase: PhaseAccounting::default(),
+            program: PhaseAccounting::default(),
+        }
+    }
+
+    fn phase_totals(layout: &FlashLayout) -> (u64, u64, u64) {
+        let fill_total: u64 = layout.fills().iter().map(|f| f.size()).sum();
+        let sector_total: u64 = layout.sectors().iter().map(|s| s.size()).sum();
+        let page_total: u64 = layout.pages().iter().map(|p| p.size()).sum();
+        (fill_total, sector_total, page_total)
+    }
+
+    fn record(&mut self, phase: Phase, size: u32, time: Duration) {
+        let accounting = match phase {
+            Phase::Fill => &mut self.fill,
+            Phase::Erase => &mut self.erase,
+            Phase::Program => &mut self.program,
+        };
+
+        accounting.bytes_done += size as u64;
+        accounting.elapsed += time;
+
+        let percent = if accounting.total_bytes == 0 {
+            100.0
+        } else {
+            (accounting.bytes_done as f32 / accounting.total_bytes as f32) * 100.0
+        };
+        let secs = accounting.elapsed.as_secs_f32().max(f32::EPSILON);
+        let throughput_kib_s = (accounting.bytes_done as f32 / 1024.0) / secs;
+
+        self.sink.update(ProgressUpdate::PhaseProgress {
+            phase,
+            bytes_done: accounting.bytes_done,
+            total_bytes: accounting.total_bytes,
+            percent,
+            throughput_kib_s,
+        });
+    }
+
+    /// Handle a single `ProgressEvent`, updating accounting and forwarding a
+    /// structured [`ProgressUpdate`] to the sink.
+    pub fn handle(&mut self, event: ProgressEvent) {
+        use ProgressEvent::*;
+        match event {
+            Initialized { flash_layout } => {
+                let (fill_total, erase_total, program_total) = Self::phase_totals(&flash_layout);
+                self.fill.total_bytes = fill_total;
+                self.erase.total_bytes = erase_total;
+                self.program.total_bytes = program_total;
+            }
+
+            StartedFilling => self.sink.update(ProgressUpdate::PhaseStarted {
+                phase: Phase::Fill,
+                total_bytes: self.fill.total_bytes,
+            }),
+            PageFilled { size, time } => self.record(Phase::Fill, size, time),
+            FailedFilling => self.sink.update(ProgressUpdate::PhaseFailed { phase: Phase::Fill }),
+            FinishedFilling => self.sink.update(ProgressUpdate::PhaseFinished { phase: Phase::Fill }),
+
+            StartedErasing => self.sink.update(ProgressUpdate::PhaseStarted {
+                phase: Phase::Erase,
+                total_bytes: self.erase.total_bytes,
+            }),
+            SectorErased { size, time } => self.record(Phase::Erase, size, time),
+            FailedErasing => self.sink.update(ProgressUpdate::PhaseFailed { phase: Phase::Erase }),
+            FinishedErasing => self.sink.update(ProgressUpdate::PhaseFinished { phase: Phase::Erase }),
+
+            StartedProgramming => self.sink.update(ProgressUpdate::PhaseStarted {
+                phase: Phase::Program,
+                total_bytes: self.program.total_bytes,
+            }),
+            PageProgrammed { size, time } => self.record(Phase::Program, size, time),
+            FailedProgramming => self.sink.update(ProgressUpdate::PhaseFailed { phase: Phase::Program }),
+            FinishedProgramming => {
+                self.sink.update(ProgressUpdate::PhaseFinished { phase: Phase::Program });
+                self.sink.update(ProgressUpdate::Summary {
+                    phases: vec![
+                        (Phase::Fill, self.fill.bytes_done, self.fill.elapsed),
+                        (Phase::Erase, self.erase.bytes_done, self.erase.elapsed),
+                        (Phase::Program, self.program.bytes_done, self.program.elapsed),
+                    ],
+                });
+            }
+        }
+    }
+}
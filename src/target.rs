@@ -0,0 +1,106 @@
+//! Target auto-detection and session bring-up.
+//!
+//! The three supported families (`STM32F1xx`, `STM32F2xx`, `STM32L4xx`) are shipped as
+//! embedded YAML target descriptions. The ARM DP IDCODE alone isn't enough to tell the
+//! F2 and L4 families apart (they share `0x2ba0_1477`), so after attaching we also read
+//! the STM32-specific `DBGMCU_IDCODE` register and use its device-ID field as the
+//! tiebreaker, looking the result up in `device_ids` to resolve both the exact target
+//! and a human-readable density string.
+
+use std::{collections::HashMap, path::Path};
+
+use probe_rs::{
+    architecture::arm::{ApAddress, DpAddress},
+    config::{add_target_from_yaml, get_target_by_name},
+    MemoryInterface, Permissions, Probe, Session, Target,
+};
+
+const STM32F1: &[u8] = include_bytes!("../res/STM32F1xx.yaml");
+const STM32F2: &[u8] = include_bytes!("../res/STM32F2xx.yaml");
+const STM32L4: &[u8] = include_bytes!("../res/STM32L4xx.yaml");
+
+const STM32F1xID: u32 = 0x1ba01477;
+const STM32F2xID: u32 = 0x2ba01477;
+const STM32L4xID: u32 = 0x2ba01477;
+
+/// Address of the `DBGMCU_IDCODE` register, common to the whole STM32 Cortex-M range.
+/// Bits `[11:0]` hold the device ID, which disambiguates families that share an ARM
+/// DP IDCODE (F2 and L4 both report `0x2ba01477`).
+const DBGMCU_IDCODE: u64 = 0xE004_2000;
+const DBGMCU_IDCODE_DEV_ID_MASK: u32 = 0x0FFF;
+
+fn device_ids() -> HashMap<u32, &'static str> {
+    HashMap::from([
+        (0x412, "STM32F10xxx Low Density"),
+        (0x410, "STM32F10xxx Medium Density"),
+        (0x414, "STM32F10xxx High Density"),
+        (0x430, "STM32F10xxx XL Density"),
+        (0x418, "STM32F10xxx Connectivity"),
+        (0x411, "STM32F20xxx / STM32F21xxx"),
+        (0x435, "STM32L43xxx / STM32L44xxx"),
+        (0x462, "STM32L45xxx / STM32L46xxx"),
+        (0x464, "STM32L41xxx / STM32L42xxx"),
+    ])
+}
+
+/// Extract the embedded target YAML matching `dp_idcode`/`device_id` to disk and
+/// register it with probe-rs, returning the resolved [`Target`] plus the
+/// human-readable density string from `device_ids`.
+fn resolve_target(dp_idcode: u32, device_id: u32) -> Result<(Target, &'static str), probe_rs::Error> {
+    let density = device_ids()
+        .get(&device_id)
+        .copied()
+        .ok_or_else(|| probe_rs::Error::ChipNotFound(format!("Unknown device ID: {:#05x}", device_id)))?;
+
+    let (yaml, target_name) = match (dp_idcode, device_id) {
+        (STM32F1xID, 0x412) => (STM32F1, "STM32F101C4"),
+        (STM32F1xID, 0x410) => (STM32F1, "STM32F103C8"),
+        (STM32F1xID, 0x414) => (STM32F1, "STM32F103RC"),
+        (STM32F1xID, 0x430) => (STM32F1, "STM32F101RG"),
+        (STM32F1xID, 0x418) => (STM32F1, "STM32F105RC"),
+        (STM32L4xID, 0x435) => (STM32L4, "STM32L433RCTx"),
+        (STM32L4xID, 0x462) => (STM32L4, "STM32L452RETx"),
+        (STM32L4xID, 0x464) => (STM32L4, "STM32L412KBTx"),
+        (STM32F2xID, _) => (STM32F2, "STM32F215RGTx"),
+        _ => {
+            return Err(probe_rs::Error::ChipNotFound(format!(
+                "Unsupported DP IDCODE/device ID combination: {:#010x}/{:#05x}",
+                dp_idcode, device_id
+            )))
+        }
+    };
+
+    std::fs::write("./target.yaml", yaml).expect("Failed to extract target description");
+    add_target_from_yaml(Path::new("./target.yaml"))?;
+
+    Ok((get_target_by_name(target_name)?, density))
+}
+
+/// Attach to `probe`, auto-detect which supported STM32 is connected and open a
+/// [`Session`] against it.
+///
+/// Returns the resolved [`Target`] and a human-readable density string alongside the
+/// session, so callers don't have to hand-pick a target name up front.
+pub fn identify_and_attach(mut probe: Probe) -> Result<(Session, Target, &'static str), probe_rs::Error> {
+    probe.attach_to_unspecified()?;
+
+    let mut interface = probe
+        .try_into_arm_interface()
+        .map_err(|(_, err)| probe_rs::Error::from(err))?
+        .initialize_unspecified()?;
+
+    let dp_idcode = interface
+        .read_chip_info_from_rom_table(DpAddress::Default)?
+        .map(|info| info.part as u32)
+        .unwrap_or_default();
+
+    let mut memory = interface.memory_interface(ApAddress::default())?;
+    let device_id = memory.read_word_32(DBGMCU_IDCODE)? & DBGMCU_IDCODE_DEV_ID_MASK;
+
+    let (target, density) = resolve_target(dp_idcode, device_id)?;
+
+    let probe = interface.close();
+    let session = probe.attach(target.clone(), Permissions::new().allow_erase_all())?;
+
+    Ok((session, target, density))
+}